@@ -1,5 +1,7 @@
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::RwLock;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
@@ -10,6 +12,7 @@ use ast_grep_core::{language::Language, AstGrep, Doc, Node, NodeMatch, StrDoc};
 
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub use tower_lsp::{LspService, Server};
 
@@ -21,13 +24,24 @@ struct VersionedAst<D: Doc> {
   root: AstGrep<D>,
 }
 
+type LoadRules<L> =
+  Box<dyn Fn() -> std::result::Result<RuleCollection<L>, String> + Send + Sync + 'static>;
+
 pub struct Backend<L: LSPLang> {
   client: Client,
   map: DashMap<String, VersionedAst<StrDoc<L>>>,
-  rules: std::result::Result<RuleCollection<L>, String>,
+  // behind a lock so `did_change_watched_files`/`did_change_configuration` can
+  // hot-reload it without restarting the server
+  rules: RwLock<std::result::Result<RuleCollection<L>, String>>,
+  load_rules: LoadRules<L>,
+  // Bumped on every rule reload so pull-diagnostic result ids change even when
+  // a document's version hasn't moved; otherwise a client polling with the old
+  // `previous_result_id` would keep getting `Unchanged` against a stale rule set.
+  rules_generation: AtomicU64,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MatchRequest {
   pattern: String,
 }
@@ -49,6 +63,22 @@ impl MatchResult {
   }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeActionData {
+  text_document: TextDocumentIdentifier,
+  rule_id: String,
+  range: Range,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DumpRequest {
+  text_document: TextDocumentIdentifier,
+  pattern: Option<String>,
+  rule_id: Option<String>,
+}
+
 impl<L: LSPLang> Backend<L> {
   pub async fn search(&self, params: MatchRequest) -> Result<Vec<MatchResult>> {
     let matcher = params.pattern;
@@ -64,6 +94,97 @@ impl<L: LSPLang> Backend<L> {
     }
     Ok(match_result)
   }
+
+  pub async fn search_in_workspace(&self, params: MatchRequest) -> Result<Vec<MatchResult>> {
+    let matcher = params.pattern;
+    let mut match_result = vec![];
+    for folder in self.client.workspace_folders().await?.into_iter().flatten() {
+      let Ok(root) = folder.uri.to_file_path() else {
+        continue;
+      };
+      for path in walk_files(&root) {
+        let Ok(uri) = Url::from_file_path(&path) else {
+          continue;
+        };
+        let Some(lang) = Self::infer_lang_from_uri(&uri) else {
+          continue;
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+          continue;
+        };
+        let root = AstGrep::new(text, lang);
+        for matched_node in root.root().find_all(matcher.as_str()) {
+          let content = matched_node.text().to_string();
+          let range = convert_node_to_range(&matched_node);
+          match_result.push(MatchResult::new(uri.to_string(), range, content));
+        }
+      }
+    }
+    Ok(match_result)
+  }
+
+  /// Dumps the kind tree and matched text for the first node a pattern or rule id
+  /// matches in `text_document`, so rule authors can inspect a match without
+  /// leaving the editor.
+  pub async fn debug_query(&self, params: DumpRequest) -> Result<String> {
+    let uri = params.text_document.uri.as_str();
+    let versioned = self.map.get(uri).ok_or_else(|| {
+      tower_lsp::jsonrpc::Error::invalid_params(format!("document not open: {uri}"))
+    })?;
+    let matched = if let Some(pattern) = &params.pattern {
+      versioned.root.root().find(pattern.as_str())
+    } else if let Some(rule_id) = &params.rule_id {
+      let rules_guard = self.rules.read().await;
+      let rules = rules_guard
+        .as_ref()
+        .map_err(|e| tower_lsp::jsonrpc::Error::invalid_params(e.clone()))?;
+      let path = params.text_document.uri.to_file_path().unwrap_or_default();
+      let config = rules
+        .for_path(&path)
+        .find(|c| c.id == *rule_id)
+        .ok_or_else(|| tower_lsp::jsonrpc::Error::invalid_params(format!("no such rule: {rule_id}")))?;
+      versioned.root.root().find(&config.matcher)
+    } else {
+      return Err(tower_lsp::jsonrpc::Error::invalid_params(
+        "either `pattern` or `ruleId` must be set",
+      ));
+    };
+    let Some(matched) = matched else {
+      return Ok(String::from("no match found"));
+    };
+    let mut dump = String::new();
+    dump_kind_tree(&matched, 0, &mut dump);
+    dump.push('\n');
+    dump.push_str(matched.text().as_ref());
+    Ok(dump)
+  }
+}
+
+fn walk_files(dir: &PathBuf) -> Vec<PathBuf> {
+  let mut files = vec![];
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return files;
+  };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    if path.is_dir() {
+      if path.file_name().is_some_and(|n| n == ".git") {
+        continue;
+      }
+      files.extend(walk_files(&path));
+    } else {
+      files.push(path);
+    }
+  }
+  files
+}
+
+fn dump_kind_tree<D: Doc>(node: &Node<D>, depth: usize, out: &mut String) {
+  use std::fmt::Write;
+  let _ = writeln!(out, "{}{}", "  ".repeat(depth), node.kind());
+  for child in node.children() {
+    dump_kind_tree(&child, depth + 1, out);
+  }
 }
 
 const FALLBACK_CODE_ACTION_PROVIDER: Option<CodeActionProviderCapability> =
@@ -71,6 +192,8 @@ const FALLBACK_CODE_ACTION_PROVIDER: Option<CodeActionProviderCapability> =
 
 const SOURCE_FIX_ALL_AST_GREP: CodeActionKind = CodeActionKind::new("source.fixAll.ast-grep");
 
+const SCAN_WORKSPACE_COMMAND: &str = "ast-grep.scanWorkspace";
+
 fn code_action_provider(
   client_capability: &ClientCapabilities,
 ) -> Option<CodeActionProviderCapability> {
@@ -104,10 +227,23 @@ impl<L: LSPLang> LanguageServer for Backend<L> {
         version: None,
       }),
       capabilities: ServerCapabilities {
-        // TODO: change this to incremental
+        // TODO: change this to incremental once ast_grep_core exposes an
+        // `AstGrep`/`StrDoc` edit API to reparse against the previous tree;
+        // until then every change is a full reparse, so FULL is the only
+        // sync mode that's actually correct.
         text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
         code_action_provider: code_action_provider(&params.capabilities)
           .or(FALLBACK_CODE_ACTION_PROVIDER),
+        execute_command_provider: Some(ExecuteCommandOptions {
+          commands: vec![SCAN_WORKSPACE_COMMAND.to_string()],
+          work_done_progress_options: Default::default(),
+        }),
+        diagnostic_provider: Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+          identifier: Some("ast-grep".to_string()),
+          inter_file_dependencies: false,
+          workspace_diagnostics: false,
+          work_done_progress_options: Default::default(),
+        })),
         ..ServerCapabilities::default()
       },
     })
@@ -120,7 +256,7 @@ impl<L: LSPLang> LanguageServer for Backend<L> {
       .await;
 
     // Report errors loading config once, upon initialization
-    if let Err(error) = &self.rules {
+    if let Err(error) = &*self.rules.read().await {
       // popup message
       self
         .client
@@ -138,6 +274,8 @@ impl<L: LSPLang> LanguageServer for Backend<L> {
         )
         .await;
     }
+
+    self.register_rule_file_watcher().await;
   }
 
   async fn shutdown(&self) -> Result<()> {
@@ -156,6 +294,7 @@ impl<L: LSPLang> LanguageServer for Backend<L> {
       .client
       .log_message(MessageType::INFO, "configuration changed!")
       .await;
+    self.reload_rules().await;
   }
 
   async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
@@ -163,6 +302,7 @@ impl<L: LSPLang> LanguageServer for Backend<L> {
       .client
       .log_message(MessageType::INFO, "watched files have changed!")
       .await;
+    self.reload_rules().await;
   }
   async fn did_open(&self, params: DidOpenTextDocumentParams) {
     self
@@ -198,6 +338,24 @@ impl<L: LSPLang> LanguageServer for Backend<L> {
       .await;
     Ok(self.on_code_action(params).await)
   }
+
+  async fn code_action_resolve(&self, action: CodeAction) -> Result<CodeAction> {
+    Ok(self.on_code_action_resolve(action.clone()).await.unwrap_or(action))
+  }
+
+  async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+    if params.command == SCAN_WORKSPACE_COMMAND {
+      self.scan_workspace().await;
+    }
+    Ok(None)
+  }
+
+  async fn diagnostic(
+    &self,
+    params: DocumentDiagnosticParams,
+  ) -> Result<DocumentDiagnosticReportResult> {
+    Ok(self.on_diagnostic(params).await)
+  }
 }
 
 fn convert_node_to_range<D: Doc>(node_match: &Node<D>) -> Range {
@@ -223,6 +381,24 @@ fn get_non_empty_message<L: Language>(rule: &RuleConfig<L>) -> String {
     rule.message.to_string()
   }
 }
+fn compute_diagnostics<L: Language>(
+  root: &AstGrep<StrDoc<L>>,
+  rules: &RuleCollection<L>,
+  path: &std::path::Path,
+  uri: &Url,
+) -> Vec<Diagnostic> {
+  let mut diagnostics = vec![];
+  let scan = CombinedScan::new(rules.for_path(path));
+  let hit_set = scan.all_kinds();
+  let matches = scan.scan(root, hit_set, false).matches;
+  for (id, ms) in matches {
+    let rule = scan.get_rule(id);
+    let to_diagnostic = |m| convert_match_to_diagnostic(m, rule, uri);
+    diagnostics.extend(ms.into_iter().map(to_diagnostic));
+  }
+  diagnostics
+}
+
 fn convert_match_to_diagnostic<L: Language>(
   node_match: NodeMatch<StrDoc<L>>,
   rule: &RuleConfig<L>,
@@ -275,37 +451,132 @@ fn url_to_code_description(url: &Option<String>) -> Option<CodeDescription> {
 }
 
 impl<L: LSPLang> Backend<L> {
-  pub fn new(client: Client, rules: std::result::Result<RuleCollection<L>, String>) -> Self {
+  pub fn new(
+    client: Client,
+    load_rules: impl Fn() -> std::result::Result<RuleCollection<L>, String> + Send + Sync + 'static,
+  ) -> Self {
+    let rules = load_rules();
     Self {
       client,
-      rules,
+      rules: RwLock::new(rules),
+      load_rules: Box::new(load_rules),
       map: DashMap::new(),
+      rules_generation: AtomicU64::new(0),
     }
   }
-  async fn publish_diagnostics(&self, uri: Url, versioned: &VersionedAst<StrDoc<L>>) -> Option<()> {
-    let mut diagnostics = vec![];
-    let path = uri.to_file_path().ok()?;
 
-    let rules = match &self.rules {
-      Ok(rules) => rules.for_path(&path),
-      Err(_) => {
-        return Some(());
-      }
+  // Registers a watcher for rule config files so `did_change_watched_files`
+  // can reload `rules` without the server being restarted.
+  async fn register_rule_file_watcher(&self) {
+    let options = DidChangeWatchedFilesRegistrationOptions {
+      watchers: vec![
+        // Covers both `sgconfig.yml`/`sgconfig.yaml` and the individual rule
+        // files referenced from it, since rule authors mostly edit the latter
+        // when tuning a rule. One glob so a single edit doesn't double-fire.
+        FileSystemWatcher {
+          glob_pattern: GlobPattern::String("**/*.{yml,yaml}".to_string()),
+          kind: None,
+        },
+      ],
+    };
+    let Ok(register_options) = serde_json::to_value(options) else {
+      return;
+    };
+    let registration = Registration {
+      id: "ast-grep-watch-config".to_string(),
+      method: "workspace/didChangeWatchedFiles".to_string(),
+      register_options: Some(register_options),
     };
-    let scan = CombinedScan::new(rules);
-    let hit_set = scan.all_kinds();
-    let matches = scan.scan(&versioned.root, hit_set, false).matches;
-    for (id, ms) in matches {
-      let rule = scan.get_rule(id);
-      let to_diagnostic = |m| convert_match_to_diagnostic(m, rule, &uri);
-      diagnostics.extend(ms.into_iter().map(to_diagnostic));
+    if let Err(error) = self.client.register_capability(vec![registration]).await {
+      self
+        .client
+        .log_message(
+          MessageType::ERROR,
+          format!("Failed to register rule file watcher: {}", error),
+        )
+        .await;
+    }
+  }
+
+  async fn reload_rules(&self) {
+    let rules = (self.load_rules)();
+    if let Err(error) = &rules {
+      self
+        .client
+        .show_message(MessageType::ERROR, format!("Failed to load rules: {}", error))
+        .await;
+    }
+    *self.rules.write().await = rules;
+    self.rules_generation.fetch_add(1, Ordering::SeqCst);
+
+    let uris: Vec<_> = self.map.iter().map(|e| e.key().clone()).collect();
+    for uri in uris {
+      let Some(versioned) = self.map.get(&uri) else {
+        continue;
+      };
+      let Ok(url) = Url::parse(&uri) else {
+        continue;
+      };
+      self.publish_diagnostics(url, &versioned).await;
     }
+  }
+
+  async fn publish_diagnostics(&self, uri: Url, versioned: &VersionedAst<StrDoc<L>>) -> Option<()> {
+    let path = uri.to_file_path().ok()?;
+    let diagnostics = match &*self.rules.read().await {
+      Ok(rules) => compute_diagnostics(&versioned.root, rules, &path, &uri),
+      Err(_) => return Some(()),
+    };
     self
       .client
       .publish_diagnostics(uri, diagnostics, Some(versioned.version))
       .await;
     Some(())
   }
+
+  // `textDocument/diagnostic` pull counterpart to `publish_diagnostics`. The
+  // document version, combined with the current rules generation, doubles as
+  // a stable result id: if neither has moved since `previous_result_id`, the
+  // client already has up-to-date diagnostics.
+  async fn on_diagnostic(&self, params: DocumentDiagnosticParams) -> DocumentDiagnosticReportResult {
+    let uri = params.text_document.uri;
+    let generation = self.rules_generation.load(Ordering::SeqCst);
+    let result_id = self
+      .map
+      .get(uri.as_str())
+      .map(|v| format!("{}:{}", v.version, generation));
+
+    if result_id.is_some() && result_id == params.previous_result_id {
+      return DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(
+        RelatedUnchangedDocumentDiagnosticReport {
+          related_documents: None,
+          unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+            result_id: result_id.unwrap_or_default(),
+          },
+        },
+      ));
+    }
+
+    let items = self.compute_pull_diagnostics(&uri).await.unwrap_or_default();
+    DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(
+      RelatedFullDocumentDiagnosticReport {
+        related_documents: None,
+        full_document_diagnostic_report: FullDocumentDiagnosticReport {
+          result_id,
+          items,
+        },
+      },
+    ))
+  }
+
+  async fn compute_pull_diagnostics(&self, uri: &Url) -> Option<Vec<Diagnostic>> {
+    let versioned = self.map.get(uri.as_str())?;
+    let path = uri.to_file_path().ok()?;
+    let rules_guard = self.rules.read().await;
+    let rules = rules_guard.as_ref().ok()?;
+    Some(compute_diagnostics(&versioned.root, rules, &path, uri))
+  }
+
   async fn on_open(&self, params: DidOpenTextDocumentParams) -> Option<()> {
     let text_doc = params.text_document;
     let uri = text_doc.uri.as_str().to_owned();
@@ -331,13 +602,15 @@ impl<L: LSPLang> Backend<L> {
   async fn on_change(&self, params: DidChangeTextDocumentParams) -> Option<()> {
     let text_doc = params.text_document;
     let uri = text_doc.uri.as_str();
+    // We only advertise `TextDocumentSyncKind::FULL`, so the client always
+    // sends the whole document as a single change with no `range`.
     let text = &params.content_changes[0].text;
     self
       .client
       .log_message(MessageType::LOG, "Parsing changed doc.")
       .await;
     let lang = Self::infer_lang_from_uri(&text_doc.uri)?;
-    let root = AstGrep::new(text, lang);
+    let root = AstGrep::new(text.clone(), lang);
     let mut versioned = self.map.get_mut(uri)?;
     // skip old version update
     if versioned.version > text_doc.version {
@@ -403,27 +676,49 @@ impl<L: LSPLang> Backend<L> {
 
   async fn on_code_action(&self, params: CodeActionParams) -> Option<CodeActionResponse> {
     let text_doc = params.text_document;
-    let path = text_doc.uri.to_file_path().ok()?;
     let diagnostics = params.context.diagnostics;
-    let error_id_to_ranges = Self::build_error_id_to_ranges(diagnostics);
     let mut response = CodeActionResponse::new();
 
     let code_action = params.context.only.as_ref()?.first()?.clone();
 
-    // we only handle these code_actions
-    // 1. QuickFix
-    // 2. "source.fixAll" and "source.fixAll.ast-grep"
-    if code_action != CodeActionKind::QUICKFIX
-      && code_action != CodeActionKind::SOURCE_FIX_ALL
-      && code_action != SOURCE_FIX_ALL_AST_GREP
-    {
+    // QuickFix actions are returned one per diagnostic, without a computed edit:
+    // the concrete `TextEdit` is filled in lazily by `code_action_resolve`.
+    if code_action == CodeActionKind::QUICKFIX {
+      for diagnostic in diagnostics {
+        let Some(NumberOrString::String(rule_id)) = diagnostic.code.clone() else {
+          continue;
+        };
+        let data = CodeActionData {
+          text_document: text_doc.clone(),
+          rule_id,
+          range: diagnostic.range,
+        };
+        let action = CodeAction {
+          title: diagnostic.message.clone(),
+          command: None,
+          diagnostics: Some(vec![diagnostic]),
+          edit: None,
+          disabled: None,
+          kind: Some(CodeActionKind::QUICKFIX),
+          is_preferred: Some(true),
+          data: serde_json::to_value(data).ok(),
+        };
+        response.push(CodeActionOrCommand::from(action));
+      }
       return Some(response);
     }
 
-    let Ok(rules) = &self.rules else {
+    // "source.fixAll" and "source.fixAll.ast-grep" eagerly fix every matched rule at once
+    if code_action != CodeActionKind::SOURCE_FIX_ALL && code_action != SOURCE_FIX_ALL_AST_GREP {
       return Some(response);
-    };
+    }
 
+    let path = text_doc.uri.to_file_path().ok()?;
+    let rules_guard = self.rules.read().await;
+    let Ok(rules) = &*rules_guard else {
+      return Some(response);
+    };
+    let error_id_to_ranges = Self::build_error_id_to_ranges(diagnostics);
     let changes = self.compute_all_fixes(text_doc, error_id_to_ranges, rules, path);
 
     let edit = Some(WorkspaceEdit {
@@ -446,6 +741,27 @@ impl<L: LSPLang> Backend<L> {
     Some(response)
   }
 
+  // Recomputes the `TextEdit` for a single QuickFix action identified by `data`,
+  // set on the action by `on_code_action` above.
+  async fn on_code_action_resolve(&self, action: CodeAction) -> Option<CodeAction> {
+    let data: CodeActionData = serde_json::from_value(action.data.clone()?).ok()?;
+    let path = data.text_document.uri.to_file_path().ok()?;
+    let rules_guard = self.rules.read().await;
+    let Ok(rules) = &*rules_guard else {
+      return Some(action);
+    };
+    let error_id_to_ranges = HashMap::from([(data.rule_id, vec![data.range])]);
+    let changes = self.compute_all_fixes(data.text_document, error_id_to_ranges, rules, path);
+    Some(CodeAction {
+      edit: Some(WorkspaceEdit {
+        changes,
+        document_changes: None,
+        change_annotations: None,
+      }),
+      ..action
+    })
+  }
+
   fn build_error_id_to_ranges(diagnostics: Vec<Diagnostic>) -> HashMap<String, Vec<Range>> {
     let mut error_id_to_ranges = HashMap::new();
     for diagnostic in diagnostics {
@@ -464,6 +780,95 @@ impl<L: LSPLang> Backend<L> {
     let path = uri.to_file_path().ok()?;
     L::from_path(path)
   }
+
+  // Walks every workspace folder, scans each file with the loaded rules, and
+  // publishes diagnostics for it; files that are already open are scanned
+  // from their live buffer rather than from disk (see `scan_file`). Reports
+  // progress via `WorkDoneProgress` since this can take a while on large
+  // repositories.
+  async fn scan_workspace(&self) -> Option<()> {
+    let rules_guard = self.rules.read().await;
+    let rules = rules_guard.as_ref().ok()?;
+    let folders = self.client.workspace_folders().await.ok()??;
+    let files: Vec<_> = folders
+      .iter()
+      .filter_map(|folder| folder.uri.to_file_path().ok())
+      .flat_map(|root| walk_files(&root))
+      .collect();
+
+    let token = NumberOrString::String(SCAN_WORKSPACE_COMMAND.to_string());
+    self
+      .client
+      .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+        token: token.clone(),
+      })
+      .await
+      .ok()?;
+    self
+      .send_progress(
+        &token,
+        WorkDoneProgress::Begin(WorkDoneProgressBegin {
+          title: "ast-grep: scanning workspace".to_string(),
+          cancellable: Some(false),
+          message: None,
+          percentage: Some(0),
+        }),
+      )
+      .await;
+
+    let total = files.len().max(1);
+    for (done, path) in files.iter().enumerate() {
+      if let Some(uri) = self.scan_file(path, rules).await {
+        let percentage = ((done + 1) * 100 / total) as u32;
+        self
+          .send_progress(
+            &token,
+            WorkDoneProgress::Report(WorkDoneProgressReport {
+              cancellable: Some(false),
+              message: Some(uri.to_string()),
+              percentage: Some(percentage),
+            }),
+          )
+          .await;
+      }
+    }
+
+    self
+      .send_progress(&token, WorkDoneProgress::End(WorkDoneProgressEnd { message: None }))
+      .await;
+    Some(())
+  }
+
+  async fn scan_file(&self, path: &PathBuf, rules: &RuleCollection<L>) -> Option<Url> {
+    let uri = Url::from_file_path(path).ok()?;
+    // Already open: `on_open`/`on_change` keep diagnostics for the live buffer
+    // up to date, so publish from that buffer instead of clobbering them with
+    // a stale on-disk read stamped with a `None` version.
+    if let Some(versioned) = self.map.get(uri.as_str()) {
+      let diagnostics = compute_diagnostics(&versioned.root, rules, path, &uri);
+      self
+        .client
+        .publish_diagnostics(uri.clone(), diagnostics, Some(versioned.version))
+        .await;
+      return Some(uri);
+    }
+    let lang = Self::infer_lang_from_uri(&uri)?;
+    let text = std::fs::read_to_string(path).ok()?;
+    let root = AstGrep::new(text, lang);
+    let diagnostics = compute_diagnostics(&root, rules, path, &uri);
+    self.client.publish_diagnostics(uri.clone(), diagnostics, None).await;
+    Some(uri)
+  }
+
+  async fn send_progress(&self, token: &NumberOrString, value: WorkDoneProgress) {
+    self
+      .client
+      .send_notification::<notification::Progress>(ProgressParams {
+        token: token.clone(),
+        value: ProgressParamsValue::WorkDone(value),
+      })
+      .await;
+  }
 }
 
 #[cfg(test)]
@@ -474,7 +879,7 @@ mod test {
   use serde_json::Value;
   use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
 
-  fn start_lsp() -> (DuplexStream, DuplexStream) {
+  fn load_test_rules() -> std::result::Result<RuleCollection<SupportLang>, String> {
     let globals = GlobalRules::default();
     let config: RuleConfig<SupportLang> = from_yaml_string(
       r"
@@ -493,9 +898,15 @@ fix: |
     .unwrap()
     .pop()
     .unwrap();
-    let rc: RuleCollection<SupportLang> = RuleCollection::try_new(vec![config]).unwrap();
-    let rc_result: std::result::Result<_, String> = Ok(rc);
-    let (service, socket) = LspService::build(|client| Backend::new(client, rc_result)).finish();
+    Ok(RuleCollection::try_new(vec![config]).unwrap())
+  }
+
+  fn start_lsp() -> (DuplexStream, DuplexStream) {
+    let (service, socket) = LspService::build(|client| Backend::new(client, load_test_rules))
+      .custom_method("ast-grep/search", Backend::search)
+      .custom_method("ast-grep/searchInWorkspace", Backend::search_in_workspace)
+      .custom_method("ast-grep/debugQuery", Backend::debug_query)
+      .finish();
     let (req_client, req_server) = duplex(1024);
     let (resp_server, resp_client) = duplex(1024);
 
@@ -520,84 +931,451 @@ fix: |
     Some(&body[..length])
   }
 
-  async fn test_lsp() {
-    let initialize = r#"{
-      "jsonrpc":"2.0",
-      "id": 1,
-      "method": "initialize",
-      "params": {
-        "capabilities": {
-          "textDocumentSync": 1
-        }
+  enum SearchRequest {}
+  impl request::Request for SearchRequest {
+    type Params = MatchRequest;
+    type Result = Vec<MatchResult>;
+    const METHOD: &'static str = "ast-grep/search";
+  }
+
+  /// Drives a `Backend` over its duplex transport with typed requests and
+  /// notifications instead of hand-formatted, string-matched JSON. Also
+  /// answers the handful of requests the server sends back to the client
+  /// (`window/workDoneProgress/create`, `workspace/workspaceFolders`) so
+  /// tests can exercise server-initiated flows like `scanWorkspace`.
+  struct TestClient {
+    req: DuplexStream,
+    resp: DuplexStream,
+    next_id: i64,
+    workspace_folders: Vec<WorkspaceFolder>,
+    // messages read while waiting on one response/notification that didn't
+    // match, kept so a later wait can still observe them
+    pending: Vec<Value>,
+  }
+
+  impl TestClient {
+    fn new(req: DuplexStream, resp: DuplexStream) -> Self {
+      Self {
+        req,
+        resp,
+        next_id: 0,
+        workspace_folders: vec![],
+        pending: vec![],
       }
-    }"#;
-    let (mut req_client, mut resp_client) = start_lsp();
-    let mut buf = vec![0; 1024];
+    }
 
-    req_client
-      .write_all(req(initialize).as_bytes())
-      .await
-      .unwrap();
-    let _ = resp_client.read(&mut buf).await.unwrap();
-
-    assert!(resp(&buf).unwrap().starts_with('{'));
-
-    let save_file = r#"{
-  "jsonrpc": "2.0",
-  "id": 1,
-  "method": "textDocument/codeAction",
-  "params": {
-    "range": {
-      "end": {
-        "character": 10,
-        "line": 1
-      },
-      "start": {
-        "character": 10,
-        "line": 1
+    async fn request<R: request::Request>(&mut self, params: R::Params) -> R::Result {
+      self.next_id += 1;
+      let id = self.next_id;
+      let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": R::METHOD,
+        "params": params,
+      });
+      self.send(body).await;
+      loop {
+        let msg = self.read_message().await;
+        if msg.get("id").and_then(Value::as_i64) == Some(id) {
+          return serde_json::from_value(msg["result"].clone()).unwrap();
+        }
+        if !self.handle_server_request(&msg).await {
+          self.pending.push(msg);
+        }
       }
-    },
-    "textDocument": {
-      "uri": "file:///Users/codes/ast-grep-vscode/test.tsx"
-    },
-    "context": {
-      "diagnostics": [
-        {
-          "range": {
-            "start": {
-              "line": 0,
-              "character": 0
-            },
-            "end": {
-              "line": 0,
-              "character": 16
-            }
-          },
-          "code": "no-console-rule",
-          "source": "ast-grep",
-          "message": "No console.log"
+    }
+
+    async fn notify<N: notification::Notification>(&mut self, params: N::Params) {
+      let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": N::METHOD,
+        "params": params,
+      });
+      self.send(body).await;
+    }
+
+    async fn wait_for_publish_diagnostics(&mut self, uri: &Url) -> PublishDiagnosticsParams {
+      loop {
+        let msg = self.read_message().await;
+        if msg["method"] == notification::PublishDiagnostics::METHOD {
+          let params: PublishDiagnosticsParams = serde_json::from_value(msg["params"].clone()).unwrap();
+          if &params.uri == uri {
+            return params;
+          }
+          continue;
         }
-      ],
-      "only": ["source.fixAll"]
+        if !self.handle_server_request(&msg).await {
+          self.pending.push(msg);
+        }
+      }
+    }
+
+    // Answers requests the server sends to the client. Returns `false` (and
+    // leaves `msg` untouched) for anything else, e.g. responses to our own
+    // requests or notifications a caller is waiting on directly.
+    async fn handle_server_request(&mut self, msg: &Value) -> bool {
+      let Some(method) = msg.get("method").and_then(Value::as_str) else {
+        return false;
+      };
+      let Some(id) = msg.get("id").cloned() else {
+        return false;
+      };
+      let result = match method {
+        "window/workDoneProgress/create" => Value::Null,
+        "workspace/workspaceFolders" => serde_json::to_value(&self.workspace_folders).unwrap(),
+        _ => return false,
+      };
+      self
+        .send(serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+        .await;
+      true
+    }
+
+    async fn send(&mut self, body: Value) {
+      self
+        .req
+        .write_all(req(&body.to_string()).as_bytes())
+        .await
+        .unwrap();
+    }
+
+    async fn read_message(&mut self) -> Value {
+      if !self.pending.is_empty() {
+        return self.pending.remove(0);
+      }
+      let mut buf = vec![0; 8192];
+      let n = self.resp.read(&mut buf).await.unwrap();
+      serde_json::from_str(resp(&buf[..n]).unwrap()).unwrap()
     }
   }
-  }"#;
 
-    let mut buf = vec![0; 1024];
-    req_client
-      .write_all(req(save_file).as_bytes())
+  fn test_diagnostic() -> Diagnostic {
+    Diagnostic {
+      range: Range {
+        start: Position {
+          line: 0,
+          character: 0,
+        },
+        end: Position {
+          line: 0,
+          character: 17,
+        },
+      },
+      code: Some(NumberOrString::String("no-console-rule".to_string())),
+      code_description: None,
+      severity: None,
+      message: "No console.log".to_string(),
+      source: Some("ast-grep".to_string()),
+      tags: None,
+      related_information: None,
+      data: None,
+    }
+  }
+
+  async fn test_lsp() {
+    let (req_client, resp_client) = start_lsp();
+    let mut client = TestClient::new(req_client, resp_client);
+
+    let init = client
+      .request::<request::Initialize>(InitializeParams::default())
+      .await;
+    assert!(init.capabilities.code_action_provider.is_some());
+
+    let uri: Url = "file:///Users/codes/ast-grep-vscode/test.tsx".parse().unwrap();
+    client
+      .notify::<notification::DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+          uri: uri.clone(),
+          language_id: "typescriptreact".to_string(),
+          version: 0,
+          text: "console.log('hi')".to_string(),
+        },
+      })
+      .await;
+    client.wait_for_publish_diagnostics(&uri).await;
+
+    let params = CodeActionParams {
+      text_document: TextDocumentIdentifier { uri: uri.clone() },
+      range: Range {
+        start: Position {
+          line: 0,
+          character: 0,
+        },
+        end: Position {
+          line: 0,
+          character: 0,
+        },
+      },
+      context: CodeActionContext {
+        diagnostics: vec![test_diagnostic()],
+        only: Some(vec![CodeActionKind::QUICKFIX]),
+        trigger_kind: None,
+      },
+      work_done_progress_params: Default::default(),
+      partial_result_params: Default::default(),
+    };
+    let actions = client
+      .request::<request::CodeActionRequest>(params)
       .await
       .unwrap();
-    let _ = resp_client.read(&mut buf).await.unwrap();
-
-    let json_val: Value = serde_json::from_str(resp(&buf).unwrap()).unwrap();
+    assert_eq!(actions.len(), 1);
+    let CodeActionOrCommand::CodeAction(action) = actions.into_iter().next().unwrap() else {
+      panic!("expected a CodeAction, not a Command");
+    };
+    assert_eq!(action.kind, Some(CodeActionKind::QUICKFIX));
+    assert!(
+      action.edit.is_none(),
+      "QuickFix actions resolve their edit lazily"
+    );
 
-    // {"jsonrpc":"2.0","method":"window/logMessage","params":{"message":"run code action!","type":3}}
-    assert_eq!(json_val["method"], "window/logMessage");
+    let resolved = client
+      .request::<request::CodeActionResolveRequest>(action)
+      .await;
+    let changes = resolved
+      .edit
+      .and_then(|e| e.changes)
+      .expect("resolved action should carry a workspace edit");
+    assert_eq!(changes[&uri][0].new_text, "alert('hi')");
   }
 
   #[test]
   fn actual_test() {
     tokio::runtime::Runtime::new().unwrap().block_on(test_lsp());
   }
+
+  async fn test_search_open_document() {
+    let (req_client, resp_client) = start_lsp();
+    let mut client = TestClient::new(req_client, resp_client);
+
+    client
+      .request::<request::Initialize>(InitializeParams::default())
+      .await;
+
+    let uri: Url = "file:///workspace/test.tsx".parse().unwrap();
+    client
+      .notify::<notification::DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+          uri: uri.clone(),
+          language_id: "typescriptreact".to_string(),
+          version: 0,
+          text: "console.log('hi')".to_string(),
+        },
+      })
+      .await;
+    client.wait_for_publish_diagnostics(&uri).await;
+
+    let matches = client
+      .request::<SearchRequest>(MatchRequest {
+        pattern: "console.log($$$A)".to_string(),
+      })
+      .await;
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].uri, uri.to_string());
+  }
+
+  #[test]
+  fn search_finds_matches_in_open_documents() {
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(test_search_open_document());
+  }
+
+  async fn test_did_change_full_sync() {
+    let (req_client, resp_client) = start_lsp();
+    let mut client = TestClient::new(req_client, resp_client);
+    client
+      .request::<request::Initialize>(InitializeParams::default())
+      .await;
+
+    let uri: Url = "file:///workspace/full-sync.tsx".parse().unwrap();
+    client
+      .notify::<notification::DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+          uri: uri.clone(),
+          language_id: "typescriptreact".to_string(),
+          version: 0,
+          text: "console.log(1)".to_string(),
+        },
+      })
+      .await;
+    client.wait_for_publish_diagnostics(&uri).await;
+
+    // we only advertise `TextDocumentSyncKind::FULL`, so a change carries the
+    // whole new document text and no `range`
+    client
+      .notify::<notification::DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier {
+          uri: uri.clone(),
+          version: 1,
+        },
+        content_changes: vec![TextDocumentContentChangeEvent {
+          range: None,
+          range_length: None,
+          text: "console.log(2)".to_string(),
+        }],
+      })
+      .await;
+    client.wait_for_publish_diagnostics(&uri).await;
+
+    let matches = client
+      .request::<SearchRequest>(MatchRequest {
+        pattern: "console.log(2)".to_string(),
+      })
+      .await;
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].uri, uri.to_string());
+
+    let stale = client
+      .request::<SearchRequest>(MatchRequest {
+        pattern: "console.log(1)".to_string(),
+      })
+      .await;
+    assert!(stale.is_empty());
+  }
+
+  #[test]
+  fn did_change_reparses_full_document() {
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(test_did_change_full_sync());
+  }
+
+  async fn test_hot_reload() {
+    let (req_client, resp_client) = start_lsp();
+    let mut client = TestClient::new(req_client, resp_client);
+    client
+      .request::<request::Initialize>(InitializeParams::default())
+      .await;
+
+    let uri: Url = "file:///workspace/reload.tsx".parse().unwrap();
+    client
+      .notify::<notification::DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+          uri: uri.clone(),
+          language_id: "typescriptreact".to_string(),
+          version: 0,
+          text: "console.log('hi')".to_string(),
+        },
+      })
+      .await;
+    let first = client.wait_for_publish_diagnostics(&uri).await;
+    assert_eq!(first.diagnostics.len(), 1);
+
+    client
+      .notify::<notification::DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+        changes: vec![FileEvent {
+          uri: "file:///workspace/sgconfig.yml".parse().unwrap(),
+          typ: FileChangeType::CHANGED,
+        }],
+      })
+      .await;
+
+    // reload_rules republishes diagnostics for every open document, even
+    // though the buffer itself hasn't changed
+    let reloaded = client.wait_for_publish_diagnostics(&uri).await;
+    assert_eq!(reloaded.diagnostics.len(), 1);
+  }
+
+  #[test]
+  fn watched_file_change_triggers_reload_and_republish() {
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(test_hot_reload());
+  }
+
+  async fn test_pull_diagnostics() {
+    let (req_client, resp_client) = start_lsp();
+    let mut client = TestClient::new(req_client, resp_client);
+    client
+      .request::<request::Initialize>(InitializeParams::default())
+      .await;
+
+    let uri: Url = "file:///workspace/pull.tsx".parse().unwrap();
+    client
+      .notify::<notification::DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+          uri: uri.clone(),
+          language_id: "typescriptreact".to_string(),
+          version: 0,
+          text: "console.log('hi')".to_string(),
+        },
+      })
+      .await;
+    client.wait_for_publish_diagnostics(&uri).await;
+
+    let report = client
+      .request::<request::DocumentDiagnosticRequest>(DocumentDiagnosticParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        identifier: None,
+        previous_result_id: None,
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+      })
+      .await;
+    let DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(full)) = report
+    else {
+      panic!("expected a full report on the first pull");
+    };
+    assert_eq!(full.full_document_diagnostic_report.items.len(), 1);
+    let result_id = full.full_document_diagnostic_report.result_id.clone();
+
+    let unchanged = client
+      .request::<request::DocumentDiagnosticRequest>(DocumentDiagnosticParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        identifier: None,
+        previous_result_id: result_id,
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+      })
+      .await;
+    assert!(matches!(
+      unchanged,
+      DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(_))
+    ));
+  }
+
+  #[test]
+  fn pull_diagnostics_reports_full_then_unchanged() {
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(test_pull_diagnostics());
+  }
+
+  async fn test_scan_workspace() {
+    let (req_client, resp_client) = start_lsp();
+    let mut client = TestClient::new(req_client, resp_client);
+    client
+      .request::<request::Initialize>(InitializeParams::default())
+      .await;
+
+    let dir = std::env::temp_dir().join(format!("ast-grep-lsp-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("scan.ts");
+    std::fs::write(&file, "console.log('from disk')").unwrap();
+    client.workspace_folders = vec![WorkspaceFolder {
+      uri: Url::from_file_path(&dir).unwrap(),
+      name: "test".to_string(),
+    }];
+
+    client
+      .request::<request::ExecuteCommand>(ExecuteCommandParams {
+        command: "ast-grep.scanWorkspace".to_string(),
+        arguments: vec![],
+        work_done_progress_params: Default::default(),
+      })
+      .await;
+
+    let file_uri = Url::from_file_path(&file).unwrap();
+    let diagnostics = client.wait_for_publish_diagnostics(&file_uri).await;
+    assert_eq!(diagnostics.diagnostics.len(), 1);
+
+    std::fs::remove_dir_all(&dir).ok();
+  }
+
+  #[test]
+  fn scan_workspace_command_scans_files_on_disk() {
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(test_scan_workspace());
+  }
 }